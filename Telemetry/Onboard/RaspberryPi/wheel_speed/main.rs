@@ -1,6 +1,8 @@
 use rppal::gpio::{Gpio, Trigger};
-use std::time::{Instant, Duration};
+use rppal::pwm::{Channel, Polarity, Pwm};
+use std::time::{Instant, Duration, SystemTime, UNIX_EPOCH};
 use std::error::Error;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::sync::atomic::{Ordering, AtomicBool};
 use std::sync::{Arc, mpsc};
@@ -9,28 +11,292 @@ use std::process::Command;
 use std::fs::OpenOptions;
 use std::io::Write;
 use std::collections::VecDeque;
+use rumqttc::{Client, MqttOptions, QoS};
+use std::net::{TcpListener, TcpStream};
+use std::io::{BufRead, BufReader};
+use std::sync::Mutex;
 
-const GPIO_PIN: u8 = 17;
-const TIMEOUT_SECS: u64 = 2;
-const DEBOUNCE_MS: u64 = 20;
-const STATUS_FILE: &str = "/tmp/wheel_speed.json";
-const RPM_BUFFER_SIZE: usize = 3;  // Average over last 3 readings for smoother output
+const CONFIG_FILE: &str = "/etc/wheel_speed/config.json";
+const GPIO_PIN_MAX: u8 = 27;  // Highest usable BCM GPIO number on a Raspberry Pi
+// Stall-detection timeout is multiplied by 1000 to get milliseconds; cap it
+// well below where that multiplication could overflow u64, and well below
+// any sane "no rotation" wait besides.
+const TIMEOUT_SECS_MAX: u64 = 3600;
+
+// MQTT reconnect backoff isn't something you'd ever want to reach for over
+// the wire; broker identity/topic/QoS live in Config instead, see below.
+const MQTT_RECONNECT_BACKOFF_MS: u64 = 500;
+const MQTT_RECONNECT_BACKOFF_MAX_MS: u64 = 30_000;
+
+// SNTP clock sync, useful on a headless Pi with no RTC
+const SNTP_ENABLED: bool = true;
+const SNTP_SERVER: &str = "pool.ntp.org:123";
+const SNTP_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Closed-loop speed control: drives a motor/brake PWM output to hold target_rpm.
+// Kp/Ki/Kd are tunable over the wire, see Config below.
+const PWM_CHANNEL: Channel = Channel::Pwm0;
+const PWM_FREQUENCY_HZ: f64 = 1000.0;
+
+// Line-oriented TCP telemetry/command console
+const TCP_PORT: u16 = 9000;
+
+// Status emission is token-bucket limited so a spinning wheel at high RPM
+// doesn't flood the file/MQTT/TCP sinks; every pulse is still counted.
+const STATUS_RATE_LIMIT_PER_SEC: f64 = 10.0;
+
+/// Runtime-editable settings, loaded from `CONFIG_FILE` at startup and
+/// persisted back to disk on every change so they survive a restart. This
+/// replaces what used to be compile-time `const`s.
+#[derive(Serialize, Deserialize, Clone)]
+struct Config {
+    gpio_pin: u8,
+    timeout_secs: u64,
+    debounce_ms: u64,
+    status_file: String,
+    rpm_buffer_size: usize,
+    target_rpm: f64,
+    pid_kp: f64,
+    pid_ki: f64,
+    pid_kd: f64,
+    wheel_circumference_m: f64,
+    rate_window_secs: u64,
+    mqtt_broker_host: String,
+    mqtt_broker_port: u16,
+    mqtt_topic: String,
+    mqtt_client_id: String,
+    mqtt_qos: u8,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            gpio_pin: 17,
+            timeout_secs: 2,
+            debounce_ms: 20,
+            status_file: "/tmp/wheel_speed.json".to_string(),
+            rpm_buffer_size: 3,  // Average over last 3 readings for smoother output
+            target_rpm: 0.0,
+            pid_kp: 0.01,
+            pid_ki: 0.005,
+            pid_kd: 0.001,
+            wheel_circumference_m: 2.07,  // ~26" bicycle wheel
+            rate_window_secs: 10,
+            mqtt_broker_host: "localhost".to_string(),
+            mqtt_broker_port: 1883,
+            mqtt_topic: "ecoquest/wheel_speed".to_string(),
+            mqtt_client_id: "wheel_speed_monitor".to_string(),
+            mqtt_qos: 1,  // QoS::AtLeastOnce
+        }
+    }
+}
+
+/// Maps a stored `mqtt_qos` value (0/1/2) to the rumqttc QoS it represents.
+fn mqtt_qos_from_u8(qos: u8) -> QoS {
+    match qos {
+        0 => QoS::AtMostOnce,
+        2 => QoS::ExactlyOnce,
+        _ => QoS::AtLeastOnce,
+    }
+}
+
+fn load_config() -> Config {
+    match std::fs::read_to_string(CONFIG_FILE) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+            eprintln!("Failed to parse {}, using defaults: {}", CONFIG_FILE, e);
+            Config::default()
+        }),
+        Err(_) => Config::default(),
+    }
+}
+
+fn save_config(config: &Config) -> Result<(), Box<dyn Error>> {
+    if let Some(parent) = std::path::Path::new(CONFIG_FILE).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(CONFIG_FILE, serde_json::to_string_pretty(config)?)?;
+    Ok(())
+}
+
+/// Validates and applies one `key=value` write to the config. Rejects values
+/// that would leave the monitor in a broken state (a zero debounce, an
+/// out-of-range pin).
+fn set_config_field(config: &mut Config, key: &str, value: &str) -> Result<(), String> {
+    match key {
+        "gpio_pin" => {
+            let pin: u8 = value.parse().map_err(|_| format!("invalid gpio_pin value: {}", value))?;
+            if pin > GPIO_PIN_MAX {
+                return Err(format!("gpio_pin out of range (0-{}): {}", GPIO_PIN_MAX, pin));
+            }
+            config.gpio_pin = pin;
+        }
+        "timeout_secs" => {
+            let secs: u64 = value.parse().map_err(|_| format!("invalid timeout_secs value: {}", value))?;
+            if secs == 0 {
+                return Err("timeout_secs must be greater than zero".to_string());
+            }
+            if secs > TIMEOUT_SECS_MAX {
+                return Err(format!("timeout_secs out of range (1-{}): {}", TIMEOUT_SECS_MAX, secs));
+            }
+            config.timeout_secs = secs;
+        }
+        "debounce_ms" => {
+            let ms: u64 = value.parse().map_err(|_| format!("invalid debounce_ms value: {}", value))?;
+            if ms == 0 {
+                return Err("debounce_ms must be greater than zero".to_string());
+            }
+            config.debounce_ms = ms;
+        }
+        "status_file" => config.status_file = value.to_string(),
+        "rpm_buffer_size" => {
+            let size: usize = value.parse().map_err(|_| format!("invalid rpm_buffer_size value: {}", value))?;
+            if size == 0 {
+                return Err("rpm_buffer_size must be greater than zero".to_string());
+            }
+            config.rpm_buffer_size = size;
+        }
+        "target_rpm" => {
+            let rpm: f64 = value.parse().map_err(|_| format!("invalid target_rpm value: {}", value))?;
+            config.target_rpm = rpm;
+        }
+        "pid_kp" => {
+            config.pid_kp = value.parse().map_err(|_| format!("invalid pid_kp value: {}", value))?;
+        }
+        "pid_ki" => {
+            config.pid_ki = value.parse().map_err(|_| format!("invalid pid_ki value: {}", value))?;
+        }
+        "pid_kd" => {
+            config.pid_kd = value.parse().map_err(|_| format!("invalid pid_kd value: {}", value))?;
+        }
+        "wheel_circumference_m" => {
+            let m: f64 = value.parse().map_err(|_| format!("invalid wheel_circumference_m value: {}", value))?;
+            if m <= 0.0 {
+                return Err("wheel_circumference_m must be greater than zero".to_string());
+            }
+            config.wheel_circumference_m = m;
+        }
+        "rate_window_secs" => {
+            let secs: u64 = value.parse().map_err(|_| format!("invalid rate_window_secs value: {}", value))?;
+            if secs == 0 {
+                return Err("rate_window_secs must be greater than zero".to_string());
+            }
+            config.rate_window_secs = secs;
+        }
+        "mqtt_broker_host" => config.mqtt_broker_host = value.to_string(),
+        "mqtt_broker_port" => {
+            let port: u16 = value.parse().map_err(|_| format!("invalid mqtt_broker_port value: {}", value))?;
+            config.mqtt_broker_port = port;
+        }
+        "mqtt_topic" => config.mqtt_topic = value.to_string(),
+        "mqtt_client_id" => config.mqtt_client_id = value.to_string(),
+        "mqtt_qos" => {
+            let qos: u8 = value.parse().map_err(|_| format!("invalid mqtt_qos value: {}", value))?;
+            if qos > 2 {
+                return Err(format!("mqtt_qos must be 0, 1, or 2: {}", qos));
+            }
+            config.mqtt_qos = qos;
+        }
+        _ => return Err(format!("unknown config key: {}", key)),
+    }
+    Ok(())
+}
+
+/// Maps the short wire names the TCP console has always accepted
+/// (`debounce`, `timeout`) onto their `Config` field names. Older clients
+/// speak the short form, so it has to keep working alongside the long form.
+fn normalize_config_key(key: &str) -> &str {
+    match key {
+        "debounce" => "debounce_ms",
+        "timeout" => "timeout_secs",
+        other => other,
+    }
+}
+
+fn config_field_value(config: &Config, key: &str) -> Option<String> {
+    match key {
+        "gpio_pin" => Some(config.gpio_pin.to_string()),
+        "timeout_secs" => Some(config.timeout_secs.to_string()),
+        "debounce_ms" => Some(config.debounce_ms.to_string()),
+        "status_file" => Some(config.status_file.clone()),
+        "rpm_buffer_size" => Some(config.rpm_buffer_size.to_string()),
+        "target_rpm" => Some(config.target_rpm.to_string()),
+        "pid_kp" => Some(config.pid_kp.to_string()),
+        "pid_ki" => Some(config.pid_ki.to_string()),
+        "pid_kd" => Some(config.pid_kd.to_string()),
+        "wheel_circumference_m" => Some(config.wheel_circumference_m.to_string()),
+        "rate_window_secs" => Some(config.rate_window_secs.to_string()),
+        "mqtt_broker_host" => Some(config.mqtt_broker_host.clone()),
+        "mqtt_broker_port" => Some(config.mqtt_broker_port.to_string()),
+        "mqtt_topic" => Some(config.mqtt_topic.clone()),
+        "mqtt_client_id" => Some(config.mqtt_client_id.clone()),
+        "mqtt_qos" => Some(config.mqtt_qos.to_string()),
+        _ => None,
+    }
+}
+
+fn remove_config_field(config: &mut Config, key: &str) -> Result<(), String> {
+    let default = Config::default();
+    match key {
+        "gpio_pin" => config.gpio_pin = default.gpio_pin,
+        "timeout_secs" => config.timeout_secs = default.timeout_secs,
+        "debounce_ms" => config.debounce_ms = default.debounce_ms,
+        "status_file" => config.status_file = default.status_file,
+        "rpm_buffer_size" => config.rpm_buffer_size = default.rpm_buffer_size,
+        "target_rpm" => config.target_rpm = default.target_rpm,
+        "pid_kp" => config.pid_kp = default.pid_kp,
+        "pid_ki" => config.pid_ki = default.pid_ki,
+        "pid_kd" => config.pid_kd = default.pid_kd,
+        "wheel_circumference_m" => config.wheel_circumference_m = default.wheel_circumference_m,
+        "rate_window_secs" => config.rate_window_secs = default.rate_window_secs,
+        "mqtt_broker_host" => config.mqtt_broker_host = default.mqtt_broker_host,
+        "mqtt_broker_port" => config.mqtt_broker_port = default.mqtt_broker_port,
+        "mqtt_topic" => config.mqtt_topic = default.mqtt_topic,
+        "mqtt_client_id" => config.mqtt_client_id = default.mqtt_client_id,
+        "mqtt_qos" => config.mqtt_qos = default.mqtt_qos,
+        _ => return Err(format!("unknown config key: {}", key)),
+    }
+    Ok(())
+}
 
 // Helper function for non-blocking file write
-fn write_status_nonblocking(status: serde_json::Value) -> Result<(), Box<dyn Error>> {
+fn write_status_nonblocking(status_file: &str, status: &serde_json::Value) -> Result<(), Box<dyn Error>> {
     let file = OpenOptions::new()
         .write(true)
         .create(true)
         .truncate(true)
-        .open(STATUS_FILE)?;
-    
+        .open(status_file)?;
+
     let mut writer = std::io::BufWriter::new(file);
     serde_json::to_writer(&mut writer, &status)?;
     writer.flush()?;
-    
+
     Ok(())
 }
 
+/// Queries an SNTP server once at startup and returns the clock offset
+/// (server time minus local time, in milliseconds) or `None` if the query
+/// failed, e.g. no network link yet on a headless Pi.
+fn sync_ntp_offset() -> Option<i64> {
+    let socket = std::net::UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.set_read_timeout(Some(SNTP_TIMEOUT)).ok()?;
+
+    let result = sntpc::simple_get_time(SNTP_SERVER, &socket).ok()?;
+    let server_ms = result.sec() as i64 * 1000 + (result.sec_fraction() as i64 * 1000 / u32::MAX as i64);
+    let local_ms = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_millis() as i64;
+
+    Some(server_ms - local_ms)
+}
+
+/// Current epoch time in milliseconds, corrected by the measured NTP offset.
+fn current_timestamp_ms(ntp_offset_ms: i64) -> u64 {
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0);
+
+    (now_ms + ntp_offset_ms).max(0) as u64
+}
+
 fn cleanup_gpio(pin: u8) {
     Command::new("sh")
         .arg("-c")
@@ -39,43 +305,387 @@ fn cleanup_gpio(pin: u8) {
         .ok();
 }
 
+/// Wraps a connected MQTT client together with the background thread that
+/// drives its event loop (rumqttc's synchronous `Client` still needs its
+/// `Connection` polled to actually push packets onto the wire).
+struct MqttPublisher {
+    client: Client,
+}
+
+impl MqttPublisher {
+    fn connect(host: &str, port: u16, client_id: &str) -> Self {
+        let mut mqttoptions = MqttOptions::new(client_id, host, port);
+        mqttoptions.set_keep_alive(Duration::from_secs(5));
+
+        let (client, mut connection) = Client::new(mqttoptions, 10);
+
+        // We don't care about the inbound event stream (there's nothing to
+        // subscribe to), just that the loop keeps running so publishes flush.
+        std::thread::spawn(move || {
+            for notification in connection.iter() {
+                if let Err(e) = notification {
+                    eprintln!("MQTT connection error: {}", e);
+                    break;
+                }
+            }
+        });
+
+        MqttPublisher { client }
+    }
+
+    fn publish(&mut self, topic: &str, qos: QoS, status: &serde_json::Value) -> Result<(), Box<dyn Error>> {
+        let payload = serde_json::to_vec(status)?;
+        self.client.publish(topic, qos, false, payload)?;
+        Ok(())
+    }
+}
+
+/// Keeps the wheel at `setpoint` RPM by driving a PWM duty cycle in `[0.0, 1.0]`.
+struct PidController {
+    kp: f64,
+    ki: f64,
+    kd: f64,
+    setpoint: f64,
+    integral: f64,
+    prev_error: f64,
+}
+
+impl PidController {
+    fn new(kp: f64, ki: f64, kd: f64, setpoint: f64) -> Self {
+        PidController { kp, ki, kd, setpoint, integral: 0.0, prev_error: 0.0 }
+    }
+
+    /// Changing the setpoint resets accumulated error so the old target
+    /// doesn't bleed into the new one.
+    fn set_setpoint(&mut self, setpoint: f64) {
+        if setpoint != self.setpoint {
+            self.setpoint = setpoint;
+            self.reset();
+        }
+    }
+
+    /// Changing the gains resets accumulated error so the old gains'
+    /// integral windup doesn't bleed into the new ones.
+    fn set_gains(&mut self, kp: f64, ki: f64, kd: f64) {
+        if kp != self.kp || ki != self.ki || kd != self.kd {
+            self.kp = kp;
+            self.ki = ki;
+            self.kd = kd;
+            self.reset();
+        }
+    }
+
+    fn reset(&mut self) {
+        self.integral = 0.0;
+        self.prev_error = 0.0;
+    }
+
+    /// Runs one PID step and returns the clamped PWM duty cycle to apply.
+    fn step(&mut self, current_rpm: f64, dt: f64) -> f64 {
+        if dt <= 0.0 {
+            return (self.kp * (self.setpoint - current_rpm)).clamp(0.0, 1.0);
+        }
+
+        let error = self.setpoint - current_rpm;
+        let derivative = (error - self.prev_error) / dt;
+
+        // Anti-windup: clamp the integral so Ki*integral alone can't exceed
+        // the output range, regardless of what Kp/Kd are doing.
+        let mut integral = self.integral + error * dt;
+        if self.ki != 0.0 {
+            integral = integral.clamp(0.0, 1.0 / self.ki);
+        }
+        self.integral = integral;
+        self.prev_error = error;
+
+        let output = self.kp * error + self.ki * integral + self.kd * derivative;
+        output.clamp(0.0, 1.0)
+    }
+}
+
+/// Caps status emission to a fixed rate (e.g. 10/sec) while every pulse is
+/// still counted and fed into the PID loop; only how often we *report* is
+/// limited.
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate_per_sec: f64) -> Self {
+        TokenBucket {
+            capacity: rate_per_sec,
+            tokens: rate_per_sec,
+            refill_per_sec: rate_per_sec,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn try_consume(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Drops timestamps older than `window` from the front of a sliding window.
+fn trim_window(timestamps: &mut VecDeque<Instant>, window: Duration) {
+    while let Some(&front) = timestamps.front() {
+        if front.elapsed() > window {
+            timestamps.pop_front();
+        } else {
+            break;
+        }
+    }
+}
+
+type Subscribers = Arc<Mutex<Vec<mpsc::Sender<String>>>>;
+
+/// Fans a status frame out to every connected TCP subscriber as a single
+/// newline-delimited JSON line, dropping any subscriber whose socket died.
+fn broadcast_status(subscribers: &Subscribers, status: &serde_json::Value) {
+    let line = format!("{}\n", status);
+    subscribers.lock().unwrap().retain(|s| s.send(line.clone()).is_ok());
+}
+
+/// Parses one command line against the shared config store and returns the
+/// text to send back to the client.
+fn handle_command(line: &str, config: &Arc<Mutex<Config>>) -> String {
+    match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+        ["get", key] => {
+            let c = config.lock().unwrap();
+            match config_field_value(&c, normalize_config_key(key)) {
+                Some(v) => format!("OK {}={}", key, v),
+                None => format!("ERR unknown config key: {}", key),
+            }
+        }
+        ["set", key, value] => {
+            let mut c = config.lock().unwrap();
+            match set_config_field(&mut c, normalize_config_key(key), value) {
+                Ok(()) => {
+                    let snapshot = c.clone();
+                    drop(c);
+                    if let Err(e) = save_config(&snapshot) {
+                        eprintln!("Failed to persist config: {}", e);
+                    }
+                    format!("OK {}={}", key, value)
+                }
+                Err(e) => format!("ERR {}", e),
+            }
+        }
+        ["remove", key] => {
+            let mut c = config.lock().unwrap();
+            match remove_config_field(&mut c, normalize_config_key(key)) {
+                Ok(()) => {
+                    let snapshot = c.clone();
+                    drop(c);
+                    if let Err(e) = save_config(&snapshot) {
+                        eprintln!("Failed to persist config: {}", e);
+                    }
+                    format!("OK {} reset to default", key)
+                }
+                Err(e) => format!("ERR {}", e),
+            }
+        }
+        ["report"] => {
+            let c = config.lock().unwrap();
+            serde_json::to_string(&*c).unwrap_or_else(|_| "ERR failed to serialize config".to_string())
+        }
+        _ => format!("ERR unknown command: {}", line),
+    }
+}
+
+/// Handles one connected client: registers it to receive broadcast status
+/// frames, and reads command lines off the same socket until it disconnects.
+fn handle_client(stream: TcpStream, config: Arc<Mutex<Config>>, subscribers: Subscribers) {
+    let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+    println!("TCP client connected: {}", peer);
+
+    let (line_tx, line_rx) = mpsc::channel::<String>();
+    subscribers.lock().unwrap().push(line_tx.clone());
+
+    let mut write_stream = match stream.try_clone() {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("TCP client clone failed: {}", e);
+            return;
+        }
+    };
+
+    std::thread::spawn(move || {
+        while let Ok(line) = line_rx.recv() {
+            if write_stream.write_all(line.as_bytes()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let reader = BufReader::new(stream);
+    for line in reader.lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(_) => break,
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let response = handle_command(line, &config);
+        if line_tx.send(format!("{}\n", response)).is_err() {
+            break;
+        }
+    }
+
+    println!("TCP client disconnected: {}", peer);
+}
+
+/// Starts the telemetry/command console on its own thread, accepting
+/// multiple concurrent clients.
+fn spawn_tcp_server(config: Arc<Mutex<Config>>, subscribers: Subscribers) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(("0.0.0.0", TCP_PORT))?;
+    println!("TCP console listening on port {}", TCP_PORT);
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let config = config.clone();
+                    let subscribers = subscribers.clone();
+                    std::thread::spawn(move || handle_client(stream, config, subscribers));
+                }
+                Err(e) => eprintln!("TCP accept error: {}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
+    let config = Arc::new(Mutex::new(load_config()));
+    let (gpio_pin, armed_debounce_ms) = {
+        let c = config.lock().unwrap();
+        (c.gpio_pin, c.debounce_ms)
+    };
+
     println!("Initializing GPIO...");
-    cleanup_gpio(GPIO_PIN);
+    cleanup_gpio(gpio_pin);
     std::thread::sleep(Duration::from_millis(100));
-    
+
+    let ntp_offset_ms = if SNTP_ENABLED { sync_ntp_offset() } else { None };
+    let time_synced = ntp_offset_ms.is_some();
+    let ntp_offset_ms = ntp_offset_ms.unwrap_or(0);
+    if SNTP_ENABLED {
+        match time_synced {
+            true => println!("SNTP sync OK, offset {}ms", ntp_offset_ms),
+            false => eprintln!("SNTP sync failed, timestamps may be inaccurate"),
+        }
+    }
+
     let gpio = Gpio::new()?;
-    let mut pin = match gpio.get(GPIO_PIN) {
+    let mut pin = match gpio.get(gpio_pin) {
         Ok(p) => p.into_input_pullup(),
         Err(e) => {
-            eprintln!("Failed to access GPIO {}: {}", GPIO_PIN, e);
-            cleanup_gpio(GPIO_PIN);
+            eprintln!("Failed to access GPIO {}: {}", gpio_pin, e);
+            cleanup_gpio(gpio_pin);
             return Err(e.into());
         }
     };
-    
+
     // Create channel for file writing
     let (tx, rx) = mpsc::channel();
-    
-    // Spawn file writer thread
+    // A second channel hands each status off to the MQTT thread so a down
+    // broker's retry backoff never stalls the file sink.
+    let (mqtt_tx, mqtt_rx) = mpsc::channel::<serde_json::Value>();
+
+    // Spawn the file writer thread: runs at full rate regardless of MQTT state.
+    let writer_config = config.clone();
     std::thread::spawn(move || {
         while let Ok(status) = rx.recv() {
-            if let Err(e) = write_status_nonblocking(status) {
+            let status_file = writer_config.lock().unwrap().status_file.clone();
+            if let Err(e) = write_status_nonblocking(&status_file, &status) {
                 eprintln!("Failed to write status: {}", e);
             }
+
+            // Handing off to mqtt_tx is just a queue push, not a network
+            // call, so it can never block the file sink above.
+            let _ = mqtt_tx.send(status);
+        }
+    });
+
+    // Spawn the MQTT publisher thread: owns the broker connection, backoff,
+    // and reconnect loop independently of the file sink.
+    let mqtt_config = config.clone();
+    std::thread::spawn(move || {
+        let (host, port, client_id) = {
+            let c = mqtt_config.lock().unwrap();
+            (c.mqtt_broker_host.clone(), c.mqtt_broker_port, c.mqtt_client_id.clone())
+        };
+        let mut mqtt = MqttPublisher::connect(&host, port, &client_id);
+        let mut backoff_ms = MQTT_RECONNECT_BACKOFF_MS;
+
+        while let Ok(mut status) = mqtt_rx.recv() {
+            // Drain any backlog that piled up while we were down; only the
+            // most recently received status matters.
+            while let Ok(newer) = mqtt_rx.try_recv() {
+                status = newer;
+            }
+
+            let (topic, qos) = {
+                let c = mqtt_config.lock().unwrap();
+                (c.mqtt_topic.clone(), mqtt_qos_from_u8(c.mqtt_qos))
+            };
+
+            match mqtt.publish(&topic, qos, &status) {
+                Ok(()) => backoff_ms = MQTT_RECONNECT_BACKOFF_MS,
+                Err(e) => {
+                    eprintln!("MQTT publish failed, reconnecting: {}", e);
+                    std::thread::sleep(Duration::from_millis(backoff_ms));
+                    backoff_ms = (backoff_ms * 2).min(MQTT_RECONNECT_BACKOFF_MAX_MS);
+                    let (host, port, client_id) = {
+                        let c = mqtt_config.lock().unwrap();
+                        (c.mqtt_broker_host.clone(), c.mqtt_broker_port, c.mqtt_client_id.clone())
+                    };
+                    mqtt = MqttPublisher::connect(&host, port, &client_id);
+                }
+            }
         }
     });
-    
+
+    let pwm = Pwm::with_frequency(PWM_CHANNEL, PWM_FREQUENCY_HZ, 0.0, Polarity::Normal, true)?;
+    let mut pid = {
+        let c = config.lock().unwrap();
+        PidController::new(c.pid_kp, c.pid_ki, c.pid_kd, c.target_rpm)
+    };
+
+    let subscribers: Subscribers = Arc::new(Mutex::new(Vec::new()));
+    spawn_tcp_server(config.clone(), subscribers.clone())?;
+
     let mut last_time = Instant::now();
     let mut counter = 0;
     let mut current_rpm = 0.0;
-    let mut rpm_buffer = VecDeque::with_capacity(RPM_BUFFER_SIZE);
-    
-    println!("Monitoring wheel sensor on GPIO {}...", GPIO_PIN);
-    println!("Debounce time: {}ms", DEBOUNCE_MS);
+    let mut rpm_buffer = VecDeque::with_capacity(config.lock().unwrap().rpm_buffer_size);
+    let mut armed_gpio_pin = gpio_pin;
+    let mut armed_debounce_ms = armed_debounce_ms;
+    let mut rotation_window: VecDeque<Instant> = VecDeque::new();
+    let mut distance_m = 0.0;
+    let mut status_rate_limiter = TokenBucket::new(STATUS_RATE_LIMIT_PER_SEC);
+
+    println!("Monitoring wheel sensor on GPIO {}...", gpio_pin);
+    println!("Debounce time: {}ms", armed_debounce_ms);
     println!("Press Ctrl+C to exit");
 
-    pin.set_interrupt(Trigger::FallingEdge, Some(Duration::from_millis(DEBOUNCE_MS)))?;
+    pin.set_interrupt(Trigger::FallingEdge, Some(Duration::from_millis(armed_debounce_ms)))?;
 
     // Handle Ctrl+C
     let mut signals = Signals::new(&[SIGINT])?;
@@ -90,61 +700,153 @@ fn main() -> Result<(), Box<dyn Error>> {
     });
 
     // Initialize status file
-    tx.send(json!({
+    let status = json!({
         "rpm": 0.0,
         "count": 0,
-        "timestamp": Instant::now().elapsed().as_secs(),
+        "timestamp": current_timestamp_ms(ntp_offset_ms),
+        "time_synced": time_synced,
         "running": true
-    }))?;
+    });
+    broadcast_status(&subscribers, &status);
+    tx.send(status)?;
 
     while running.load(Ordering::SeqCst) {
-        if pin.poll_interrupt(false, Some(Duration::from_millis(2000)))?.is_some() {
+        let (gpio_pin, debounce_ms, timeout_secs, target_rpm, pid_kp, pid_ki, pid_kd, rpm_buffer_size, wheel_circumference_m, rate_window_secs) = {
+            let c = config.lock().unwrap();
+            (
+                c.gpio_pin,
+                c.debounce_ms,
+                c.timeout_secs,
+                c.target_rpm,
+                c.pid_kp,
+                c.pid_ki,
+                c.pid_kd,
+                c.rpm_buffer_size,
+                c.wheel_circumference_m,
+                c.rate_window_secs,
+            )
+        };
+        let rate_window = Duration::from_secs(rate_window_secs);
+
+        if gpio_pin != armed_gpio_pin {
+            match gpio.get(gpio_pin) {
+                Ok(p) => {
+                    cleanup_gpio(armed_gpio_pin);
+                    pin = p.into_input_pullup();
+                    pin.set_interrupt(Trigger::FallingEdge, Some(Duration::from_millis(debounce_ms)))?;
+                    armed_gpio_pin = gpio_pin;
+                    armed_debounce_ms = debounce_ms;
+                    println!("Switched to GPIO {}", gpio_pin);
+                }
+                Err(e) => eprintln!("Failed to switch to GPIO {}: {}", gpio_pin, e),
+            }
+        } else if debounce_ms != armed_debounce_ms {
+            pin.set_interrupt(Trigger::FallingEdge, Some(Duration::from_millis(debounce_ms)))?;
+            armed_debounce_ms = debounce_ms;
+            println!("Debounce time updated: {}ms", debounce_ms);
+        }
+        pid.set_setpoint(target_rpm);
+        pid.set_gains(pid_kp, pid_ki, pid_kd);
+        while rpm_buffer.len() > rpm_buffer_size {
+            rpm_buffer.pop_front();
+        }
+
+        if pin.poll_interrupt(false, Some(Duration::from_millis(timeout_secs * 1000)))?.is_some() {
             let now = Instant::now();
             let duration = now.duration_since(last_time);
             let instant_rpm = 60.0 / duration.as_secs_f64();
-            
+
             // Update RPM buffer for averaging
             rpm_buffer.push_back(instant_rpm);
-            if rpm_buffer.len() > RPM_BUFFER_SIZE {
+            if rpm_buffer.len() > rpm_buffer_size {
                 rpm_buffer.pop_front();
             }
-            
+
             // Calculate average RPM
             current_rpm = rpm_buffer.iter().sum::<f64>() / rpm_buffer.len() as f64;
-            
+
             counter += 1;
             println!("Rotation {}: {:.1} RPM", counter, current_rpm);
-            
-            tx.send(json!({
-                "rpm": current_rpm,
-                "count": counter,
-                "timestamp": now.elapsed().as_secs(),
-                "running": true
-            }))?;
-            
+
+            let duty_cycle = pid.step(current_rpm, duration.as_secs_f64());
+            pwm.set_duty_cycle(duty_cycle)?;
+
+            // Sliding-window rotation rate, independent of the smoothed RPM buffer
+            rotation_window.push_back(now);
+            trim_window(&mut rotation_window, rate_window);
+            let rotations_per_min_window = rotation_window.len() as f64 * (60.0 / rate_window_secs as f64);
+
+            // Odometer: RPM -> linear speed and accumulated distance
+            distance_m += wheel_circumference_m;
+            let speed_kmh = current_rpm * wheel_circumference_m * 60.0 / 1000.0;
+
+            // Every pulse is counted above regardless; only how often we
+            // report is capped.
+            if status_rate_limiter.try_consume() {
+                let status = json!({
+                    "rpm": current_rpm,
+                    "count": counter,
+                    "timestamp": current_timestamp_ms(ntp_offset_ms),
+                    "time_synced": time_synced,
+                    "duty_cycle": duty_cycle,
+                    "speed_kmh": speed_kmh,
+                    "distance_m": distance_m,
+                    "rotations_per_min_window": rotations_per_min_window,
+                    "running": true
+                });
+                broadcast_status(&subscribers, &status);
+                tx.send(status)?;
+            }
+
             last_time = now;
         } else if current_rpm != 0.0 {
             current_rpm = 0.0;
             rpm_buffer.clear();  // Clear the buffer when stopping
-            println!("Speed: 0.0 RPM (no rotation for {} seconds)", TIMEOUT_SECS);
-            
-            tx.send(json!({
+            println!("Speed: 0.0 RPM (no rotation for {} seconds)", timeout_secs);
+
+            // No rotation: hold the drive output at zero rather than let the
+            // PID chase a stale error against a sensor that's gone quiet.
+            let duty_cycle = 0.0;
+            pwm.set_duty_cycle(duty_cycle)?;
+
+            trim_window(&mut rotation_window, rate_window);
+            let rotations_per_min_window = rotation_window.len() as f64 * (60.0 / rate_window_secs as f64);
+
+            let status = json!({
                 "rpm": 0.0,
                 "count": counter,
-                "timestamp": Instant::now().elapsed().as_secs(),
+                "timestamp": current_timestamp_ms(ntp_offset_ms),
+                "time_synced": time_synced,
+                "duty_cycle": duty_cycle,
+                "speed_kmh": 0.0,
+                "distance_m": distance_m,
+                "rotations_per_min_window": rotations_per_min_window,
                 "running": true
-            }))?;
+            });
+            broadcast_status(&subscribers, &status);
+            tx.send(status)?;
         }
     }
 
     // Final update
-    tx.send(json!({
+    pwm.set_duty_cycle(0.0)?;
+    let rate_window_secs = config.lock().unwrap().rate_window_secs;
+    trim_window(&mut rotation_window, Duration::from_secs(rate_window_secs));
+    let rotations_per_min_window = rotation_window.len() as f64 * (60.0 / rate_window_secs as f64);
+    let status = json!({
         "rpm": current_rpm,
         "count": counter,
-        "timestamp": Instant::now().elapsed().as_secs(),
+        "timestamp": current_timestamp_ms(ntp_offset_ms),
+        "time_synced": time_synced,
+        "duty_cycle": 0.0,
+        "speed_kmh": 0.0,
+        "distance_m": distance_m,
+        "rotations_per_min_window": rotations_per_min_window,
         "running": false
-    }))?;
+    });
+    broadcast_status(&subscribers, &status);
+    tx.send(status)?;
 
-    cleanup_gpio(GPIO_PIN);
+    cleanup_gpio(armed_gpio_pin);
     Ok(())
 }